@@ -1,7 +1,148 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::{debug, error, info};
-use nix::unistd::execvpe;
+use nix::unistd::{access, execvpe, AccessFlags};
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+/// Builds the environment handed to a spawned process from a principled set
+/// of rules rather than a caller-assembled blob. The parent environment can
+/// be inherited and then narrowed with an allowlist, sensitive variables
+/// stripped with a denylist, and explicit `KEY=VALUE` overrides layered on
+/// top. The produced environment has deterministic (key-sorted) ordering.
+#[derive(Default)]
+pub struct EnvBuilder {
+    inherit: bool,
+    allow: Option<HashSet<String>>,
+    deny: HashSet<String>,
+    overrides: Vec<String>,
+}
+
+impl EnvBuilder {
+    /// Start from an empty environment.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed the environment from the current process, as `std::env::vars`
+    /// iterates it.
+    pub fn inherit_parent(mut self) -> Self {
+        self.inherit = true;
+        self
+    }
+
+    /// Keep only the named variables when inheriting. Applying an allowlist
+    /// more than once unions the sets.
+    pub fn allow<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let set = self.allow.get_or_insert_with(HashSet::new);
+        set.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Strip the named variables. Useful for scrubbing `LD_PRELOAD`,
+    /// `LD_LIBRARY_PATH`, `SSH_AUTH_SOCK` and similar before exec.
+    pub fn deny<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.deny.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Layer an explicit `KEY=VALUE` override on top of the inherited set.
+    pub fn override_var<S: Into<String>>(mut self, entry: S) -> Self {
+        self.overrides.push(entry.into());
+        self
+    }
+
+    /// Layer several `KEY=VALUE` overrides on top.
+    pub fn override_vars<I, S>(mut self, entries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.overrides.extend(entries.into_iter().map(Into::into));
+        self
+    }
+
+    /// Resolve the rules into the `Vec<CString>` consumed by `execvpe`.
+    /// Each override is validated to be in `KEY=VALUE` form with a non-empty
+    /// key (split at the first `=`, so the value may contain further `=`),
+    /// and each resulting entry to contain no interior NUL byte.
+    pub fn build(self) -> Result<Vec<CString>> {
+        let mut vars: BTreeMap<String, String> = BTreeMap::new();
+
+        if self.inherit {
+            for (key, value) in std::env::vars() {
+                if self.allow.as_ref().is_some_and(|a| !a.contains(&key)) {
+                    continue;
+                }
+                if self.deny.contains(&key) {
+                    continue;
+                }
+                vars.insert(key, value);
+            }
+        }
+
+        for entry in &self.overrides {
+            // Split on the first '=' only; the value may legitimately
+            // contain further '=' characters (e.g. a D-Bus address).
+            let Some((key, value)) = entry.split_once('=') else {
+                bail!("environment override {entry:?} must be in KEY=VALUE form");
+            };
+            if key.is_empty() {
+                bail!("environment override {entry:?} has an empty variable name");
+            }
+            vars.insert(key.to_string(), value.to_string());
+        }
+
+        let mut env = Vec::with_capacity(vars.len());
+        for (key, value) in vars {
+            let entry = format!("{key}={value}");
+            let cstr = CString::new(entry.as_str()).with_context(|| {
+                format!("environment variable {entry:?} contains an interior NUL byte")
+            })?;
+            env.push(cstr);
+        }
+        Ok(env)
+    }
+}
+
+/// A path is runnable when it is a regular file the current process may
+/// execute.
+fn is_executable(path: &Path) -> bool {
+    path.is_file() && access(path, AccessFlags::X_OK).is_ok()
+}
+
+/// Resolve `command` to a concrete, absolute path. A bare program name (no
+/// `/`) is looked up against the directories in `$PATH`, in order, and the
+/// first executable match wins — mirroring how `execvpe` itself searches.
+/// When the command already contains a slash it is taken as a path and
+/// canonicalized. The result is suitable for handing to both the
+/// dependency scanner and the sandbox ruleset.
+pub fn resolve_command(command: &str) -> Result<PathBuf> {
+    if command.contains('/') {
+        return std::fs::canonicalize(command)
+            .with_context(|| format!("failed to resolve command path {command:?}"));
+    }
+
+    let path = std::env::var_os("PATH").context("PATH is not set")?;
+    for dir in std::env::split_paths(&path) {
+        let candidate = dir.join(command);
+        if is_executable(&candidate) {
+            return candidate
+                .canonicalize()
+                .with_context(|| format!("failed to canonicalize resolved command {candidate:?}"));
+        }
+    }
+
+    bail!("command {command:?} not found in PATH")
+}
 
 pub fn run(command: &str, args: &[String], env_vars: &[String]) -> Result<()> {
     info!("Executing: {} with args: {:?}", command, args);
@@ -20,13 +161,11 @@ pub fn run(command: &str, args: &[String], env_vars: &[String]) -> Result<()> {
         all_args.push(arg_cstr);
     }
 
-    // Process environment variables
-    let mut env_cstrings = Vec::new();
-    for env_var in env_vars {
-        let env_cstr = CString::new(env_var.as_str())
-            .context("Failed to convert environment variable to CString")?;
-        env_cstrings.push(env_cstr);
-    }
+    // Process environment variables through the builder so they are
+    // validated and emitted in a deterministic order.
+    let env_cstrings = EnvBuilder::new()
+        .override_vars(env_vars.iter().cloned())
+        .build()?;
 
     // Execute the command, replacing the current process
     // Use execvpe to specify environment variables explicitly
@@ -38,3 +177,52 @@ pub fn run(command: &str, args: &[String], env_vars: &[String]) -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn as_strings(env: &[CString]) -> Vec<String> {
+        env.iter()
+            .map(|c| c.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_overrides_are_sorted_and_later_wins() {
+        let env = EnvBuilder::new()
+            .override_var("FOO=1")
+            .override_var("BAR=2")
+            .override_var("FOO=3")
+            .build()
+            .unwrap();
+        assert_eq!(as_strings(&env), vec!["BAR=2", "FOO=3"]);
+    }
+
+    #[test]
+    fn test_override_requires_a_key_value_pair() {
+        assert!(EnvBuilder::new().override_var("FOO").build().is_err());
+    }
+
+    #[test]
+    fn test_override_value_may_contain_equals() {
+        let env = EnvBuilder::new()
+            .override_var("DBUS=unix:path=/run/user/1000/bus")
+            .build()
+            .unwrap();
+        assert_eq!(as_strings(&env), vec!["DBUS=unix:path=/run/user/1000/bus"]);
+    }
+
+    #[test]
+    fn test_override_rejects_empty_key() {
+        assert!(EnvBuilder::new().override_var("=value").build().is_err());
+    }
+
+    #[test]
+    fn test_override_rejects_interior_nul() {
+        assert!(EnvBuilder::new()
+            .override_var("FOO=a\0b")
+            .build()
+            .is_err());
+    }
+}
@@ -1,44 +1,35 @@
 use ::elf::endian::AnyEndian;
-use elf::{file::Class, ElfStream};
+use elf::{abi, file::Class, ElfStream};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
-    process::Command,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 const ET_DYN: u16 = 3;
 
-fn parse_interp(input: &str) -> Vec<PathBuf> {
-    let mut paths = vec![];
-    for line in input.lines() {
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        let &[name, arrow, path, _] = &fields[..] else {
-            continue;
-        };
-        // the name should not equal the path
-        // the path must not be empty
-        // an arrow must exist
-        // the path must not be a memory address in paretheses
-        if name == path || path.is_empty() || arrow != "=>" || path.starts_with('(') {
-            continue;
-        }
-        paths.push(PathBuf::from(path));
-    }
-    paths
+/// The dynamic linking information we care about from a single ELF object:
+/// the list of sonames it needs plus the rpath/runpath search hints that
+/// govern how those sonames are resolved.
+struct DynInfo {
+    needed: Vec<String>,
+    rpath: Vec<String>,
+    runpath: Vec<String>,
+    class: Class,
+    /// Value substituted for the `$PLATFORM` dynamic string token.
+    platform: &'static str,
 }
 
-fn call_interp(interp: &Path, binary_path: &str) -> Result<Vec<PathBuf>> {
-    let command_run = Command::new(interp)
-        .args(["--list", binary_path])
-        .output()
-        .context(format!(
-            "failed to call interpreter {interp:?} on binary {binary_path:?}"
-        ))?;
-    if !command_run.status.success() {
-        bail!("failed to call interpreter {interp:?} on binary {binary_path:?}: program exited with status {}", command_run.status)
+fn platform_for(e_machine: u16, class: Class) -> &'static str {
+    match e_machine {
+        abi::EM_X86_64 => "x86-64",
+        abi::EM_386 => "i686",
+        abi::EM_AARCH64 => "aarch64",
+        _ => match class {
+            Class::ELF64 => "x86-64",
+            Class::ELF32 => "i686",
+        },
     }
-    Ok(parse_interp(std::str::from_utf8(&command_run.stdout)?))
 }
 
 fn inspect_elf_interp(binary_path: &str) -> Result<Option<PathBuf>> {
@@ -92,6 +83,375 @@ fn inspect_elf_interp(binary_path: &str) -> Result<Option<PathBuf>> {
     Ok(ld_so(class))
 }
 
+/// Read the `.dynamic` section of an object and collect its `DT_NEEDED`
+/// sonames along with any `DT_RPATH`/`DT_RUNPATH` search path hints. The
+/// string-table offsets stored in those entries are resolved against the
+/// object's `.dynstr` section.
+fn read_dyn_info(binary_path: &Path) -> Result<DynInfo> {
+    let handle = std::fs::OpenOptions::new()
+        .read(true)
+        .open(binary_path)
+        .context(format!("failed to open binary {binary_path:?}"))?;
+    let mut stream = ElfStream::<AnyEndian, std::fs::File>::open_stream(handle)
+        .context(format!("failed to read binary {binary_path:?}"))?;
+
+    let class = stream.ehdr.class;
+    let platform = platform_for(stream.ehdr.e_machine, class);
+
+    // The dynamic string table lives in .dynstr; we index into it by the
+    // offsets recorded in the dynamic entries.
+    let dynstr = match stream
+        .section_header_by_name(".dynstr")
+        .context("elf section table should be parseable")?
+        .cloned()
+    {
+        Some(h) => {
+            let (data, _) = stream
+                .section_data(&h)
+                .context("unable to access .dynstr section despite the header's existence")?;
+            data.to_vec()
+        }
+        None => Vec::new(),
+    };
+
+    let lookup = |offset: u64| -> Option<String> {
+        let start = offset as usize;
+        let slice = dynstr.get(start..)?;
+        let end = slice.iter().position(|&b| b == 0).unwrap_or(slice.len());
+        std::str::from_utf8(&slice[..end]).ok().map(String::from)
+    };
+
+    let mut needed = Vec::new();
+    let mut rpath = Vec::new();
+    let mut runpath = Vec::new();
+
+    if let Some(dynamic) = stream
+        .dynamic()
+        .context("unable to parse .dynamic section")?
+    {
+        for entry in dynamic.iter() {
+            match entry.d_tag {
+                abi::DT_NEEDED => {
+                    if let Some(name) = lookup(entry.d_val()) {
+                        needed.push(name);
+                    }
+                }
+                abi::DT_RPATH => {
+                    if let Some(value) = lookup(entry.d_val()) {
+                        rpath.extend(split_paths(&value));
+                    }
+                }
+                abi::DT_RUNPATH => {
+                    if let Some(value) = lookup(entry.d_val()) {
+                        runpath.extend(split_paths(&value));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(DynInfo {
+        needed,
+        rpath,
+        runpath,
+        class,
+        platform,
+    })
+}
+
+/// Split a colon-separated rpath/runpath string, dropping empty components.
+fn split_paths(value: &str) -> Vec<String> {
+    value
+        .split(':')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Expand the dynamic string tokens understood by the loader in an rpath
+/// entry: `$ORIGIN` (the directory of the object being resolved), `$LIB`
+/// (`lib64`/`lib` depending on the ELF class), and `$PLATFORM`. Both the
+/// bare (`$ORIGIN`) and braced (`${ORIGIN}`) spellings are accepted.
+fn expand_dst(entry: &str, origin: &Path, class: Class, platform: &str) -> PathBuf {
+    let lib = match class {
+        Class::ELF64 => "lib64",
+        Class::ELF32 => "lib",
+    };
+    let origin = origin.to_string_lossy();
+
+    let expanded = entry
+        .replace("${ORIGIN}", &origin)
+        .replace("$ORIGIN", &origin)
+        .replace("${LIB}", lib)
+        .replace("$LIB", lib)
+        .replace("${PLATFORM}", platform)
+        .replace("$PLATFORM", platform);
+
+    PathBuf::from(expanded)
+}
+
+/// Search `dirs` in order for a file named `soname`, returning the first
+/// match that exists on disk.
+fn find_in_dirs(soname: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    for dir in dirs {
+        let candidate = dir.join(soname);
+        if candidate.exists() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// The search context shared across a single dependency walk: the default
+/// library directories (derived from `/etc/ld.so.conf`) and the soname map
+/// extracted from `/etc/ld.so.cache`.
+struct Resolver {
+    default_dirs: Vec<PathBuf>,
+    cache: HashMap<String, PathBuf>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        Resolver {
+            default_dirs: default_lib_dirs(),
+            cache: ld_so_cache().unwrap_or_default(),
+        }
+    }
+
+    /// Resolve a single soname to an absolute path, reproducing the loader's
+    /// search order for the object described by `info` living at `origin`:
+    /// `DT_RUNPATH` (or `DT_RPATH` when no runpath is present), then
+    /// `LD_LIBRARY_PATH`, then `ld.so.cache`, then the default system
+    /// directories.
+    fn resolve(&self, soname: &str, origin: &Path, info: &DynInfo) -> Option<PathBuf> {
+        let expand = |entries: &[String]| -> Vec<PathBuf> {
+            entries
+                .iter()
+                .map(|e| expand_dst(e, origin, info.class, info.platform))
+                .collect()
+        };
+
+        // RUNPATH takes precedence over RPATH; RPATH is only consulted when
+        // no RUNPATH entry exists.
+        let rpath_dirs = if info.runpath.is_empty() {
+            expand(&info.rpath)
+        } else {
+            expand(&info.runpath)
+        };
+        if let Some(found) = find_in_dirs(soname, &rpath_dirs) {
+            return Some(found);
+        }
+
+        if let Some(value) = std::env::var_os("LD_LIBRARY_PATH") {
+            let dirs: Vec<PathBuf> = split_paths(&value.to_string_lossy())
+                .iter()
+                .map(|e| expand_dst(e, origin, info.class, info.platform))
+                .collect();
+            if let Some(found) = find_in_dirs(soname, &dirs) {
+                return Some(found);
+            }
+        }
+
+        // The cache is how the real loader resolves most libraries and it
+        // already accounts for hwcaps/multiarch layouts.
+        if let Some(path) = self.cache.get(soname) {
+            if path.exists() {
+                return Some(path.clone());
+            }
+        }
+
+        find_in_dirs(soname, &self.default_dirs)
+    }
+}
+
+/// The default system library directories searched when an object provides
+/// no rpath/runpath and neither `LD_LIBRARY_PATH` nor the cache yield a
+/// match. The directories configured in `/etc/ld.so.conf` come first, with
+/// the FHS defaults appended so resolution still works when no config file
+/// is present.
+fn default_lib_dirs() -> Vec<PathBuf> {
+    let mut dirs = ld_so_conf_dirs();
+    for fallback in ["/lib64", "/usr/lib64", "/lib", "/usr/lib"] {
+        let path = PathBuf::from(fallback);
+        if !dirs.contains(&path) {
+            dirs.push(path);
+        }
+    }
+    dirs
+}
+
+/// Build the configured default search path by reading `/etc/ld.so.conf`
+/// and recursively following its `include` directives (whose arguments are
+/// glob patterns such as `/etc/ld.so.conf.d/*.conf`). Directories are
+/// returned in file order with duplicates removed.
+fn ld_so_conf_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut seen_files = HashSet::new();
+    parse_ld_so_conf(Path::new("/etc/ld.so.conf"), &mut dirs, &mut seen_files);
+    dirs
+}
+
+fn parse_ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>, seen_files: &mut HashSet<PathBuf>) {
+    // Guard against include cycles and repeated includes.
+    if !seen_files.insert(path.to_path_buf()) {
+        return;
+    }
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return;
+    };
+
+    for line in contents.lines() {
+        // Comments start with '#'; trailing comments are not part of the
+        // ld.so.conf grammar so we only strip full-line ones.
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("include") {
+            let pattern = rest.trim();
+            if pattern.is_empty() {
+                continue;
+            }
+            if let Ok(paths) = glob::glob(pattern) {
+                for included in paths.filter_map(Result::ok) {
+                    parse_ld_so_conf(&included, dirs, seen_files);
+                }
+            }
+            continue;
+        }
+
+        let dir = PathBuf::from(line);
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+}
+
+const CACHEMAGIC_OLD: &[u8] = b"ld.so-1.7.0\0";
+const CACHEMAGIC_NEW: &[u8] = b"glibc-ld.so.cache1.1";
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Read a NUL-terminated string at `offset` within the cache blob.
+fn cache_string(data: &[u8], offset: usize) -> Option<String> {
+    let slice = data.get(offset..)?;
+    let end = slice.iter().position(|&b| b == 0).unwrap_or(slice.len());
+    std::str::from_utf8(&slice[..end]).ok().map(String::from)
+}
+
+/// Parse `/etc/ld.so.cache`, mapping each soname to the absolute path the
+/// loader records for it. Both the legacy `ld.so-1.7.0` layout and the
+/// newer `glibc-ld.so.cache` layout are understood. A file that starts with
+/// the new-format magic is parsed as a new-format cache; a file that starts
+/// with the old-format magic is read through its old-format table only, and
+/// a new-format cache appended after it is not parsed.
+fn ld_so_cache() -> Option<HashMap<String, PathBuf>> {
+    let data = std::fs::read("/etc/ld.so.cache").ok()?;
+
+    // A pure new-format cache has its magic at offset 0 and all string
+    // offsets relative to that start.
+    if data.starts_with(CACHEMAGIC_NEW) {
+        return parse_cache_new(&data);
+    }
+
+    // Otherwise this is an old-format cache, possibly with a new-format
+    // cache appended after it. In the combined case the two caches use
+    // different string-table bases, so rather than guess the appended
+    // cache's base we read the old-format table, which ldconfig always
+    // keeps populated for compatibility.
+    if data.starts_with(CACHEMAGIC_OLD) {
+        return parse_cache_old(&data);
+    }
+
+    None
+}
+
+fn parse_cache_old(data: &[u8]) -> Option<HashMap<String, PathBuf>> {
+    // struct cache_file { char magic[12]; u32 nlibs; file_entry libs[nlibs]; }
+    // file_entry { i32 flags; u32 key; u32 value; }
+    // String offsets are relative to the start of the string table, which
+    // begins immediately after the entry array.
+    let header = CACHEMAGIC_OLD.len();
+    let nlibs = read_u32(data, header)? as usize;
+    let entries_start = header + 4;
+    let strings_start = entries_start + nlibs * 12;
+
+    let mut map = HashMap::new();
+    for i in 0..nlibs {
+        let entry = entries_start + i * 12;
+        let key = read_u32(data, entry + 4)? as usize;
+        let value = read_u32(data, entry + 8)? as usize;
+        let soname = cache_string(data, strings_start + key)?;
+        let path = cache_string(data, strings_start + value)?;
+        map.entry(soname).or_insert_with(|| PathBuf::from(path));
+    }
+    Some(map)
+}
+
+fn parse_cache_new(data: &[u8]) -> Option<HashMap<String, PathBuf>> {
+    // struct cache_file_new {
+    //   char magic[17]; char version[3]; u32 nlibs; u32 len_strings;
+    //   u8 flags; u8 padding[3]; u32 extension_offset; u32 unused[3];
+    //   file_entry_new libs[nlibs];
+    // }
+    // file_entry_new { i32 flags; u32 key; u32 value; u32 osversion; u64 hwcap; }
+    // This parses a standalone new-format cache, so string offsets are
+    // relative to the start of the blob.
+    let magic_len = CACHEMAGIC_NEW.len(); // magic + version, already concatenated
+    let nlibs = read_u32(data, magic_len)? as usize;
+    // header: magic(20) + nlibs(4) + len_strings(4) + flags+padding(4)
+    //         + extension_offset(4) + unused(12) = 48 bytes
+    let entries_start = 48;
+    let entry_size = 24;
+
+    let mut map = HashMap::new();
+    for i in 0..nlibs {
+        let entry = entries_start + i * entry_size;
+        let key = read_u32(data, entry + 4)? as usize;
+        let value = read_u32(data, entry + 8)? as usize;
+        let soname = cache_string(data, key)?;
+        let path = cache_string(data, value)?;
+        map.entry(soname).or_insert_with(|| PathBuf::from(path));
+    }
+    Some(map)
+}
+
+/// Walk the dependency graph of `binary_path` in process, collecting the
+/// absolute path of every resolvable shared library. Transitive
+/// `DT_NEEDED` entries are followed, with a seen-set over sonames to break
+/// cycles.
+fn resolve_deps(binary_path: &Path) -> Result<Vec<PathBuf>> {
+    let resolver = Resolver::new();
+    let mut resolved = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = vec![binary_path.to_path_buf()];
+
+    while let Some(object) = queue.pop() {
+        let info = read_dyn_info(&object)?;
+        let origin = object
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        for soname in &info.needed {
+            if !seen.insert(soname.clone()) {
+                continue;
+            }
+            if let Some(path) = resolver.resolve(soname, &origin, &info) {
+                queue.push(path.clone());
+                resolved.push(path);
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
 fn ld_so(class: Class) -> Option<PathBuf> {
     let arch_specific = match class {
         Class::ELF32 => "/lib32/ld-*.so.*",
@@ -114,7 +474,7 @@ pub fn list(binary_path: &str) -> Result<HashSet<PathBuf>> {
     let Some(interp) = inspect_elf_interp(binary_path)? else {
         return Ok(HashSet::default());
     };
-    let mut dependencies = call_interp(&interp, binary_path)?;
+    let mut dependencies = resolve_deps(Path::new(binary_path))?;
     dependencies.push(interp);
     follow(dependencies)
 }
@@ -150,60 +510,65 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_parse_interp_path_is_memory_address() {
-        let input = "linux-vdso.so.1 => (0x00007fdf495cd000)";
-        assert!(parse_interp(input).is_empty());
+    fn test_split_paths_drops_empty_components() {
+        assert_eq!(
+            split_paths("/opt/lib::/usr/lib:"),
+            vec![String::from("/opt/lib"), String::from("/usr/lib")]
+        );
     }
 
     #[test]
-    fn test_parse_interp_path_is_unavailable() {
-        let input = "libpcre2-8.so.0 =>  (0x00007fdf49524000)";
-        assert!(parse_interp(input).is_empty());
+    fn test_expand_dst_origin() {
+        let expanded = expand_dst(
+            "$ORIGIN/../lib",
+            Path::new("/opt/app/bin"),
+            Class::ELF64,
+            "x86-64",
+        );
+        assert_eq!(expanded, PathBuf::from("/opt/app/bin/../lib"));
     }
 
     #[test]
-    fn test_parse_interp_path_is_available() {
-        let input = "libpthread.so.0 => /lib64/libpthread.so.0 (0x00007f70f6c10000)";
-        assert_eq!(
-            parse_interp(input),
-            vec![PathBuf::from("/lib64/libpthread.so.0")]
+    fn test_expand_dst_lib_and_platform() {
+        let expanded = expand_dst(
+            "/usr/${LIB}/${PLATFORM}",
+            Path::new("/opt/app/bin"),
+            Class::ELF64,
+            "x86-64",
         );
-    }
+        assert_eq!(expanded, PathBuf::from("/usr/lib64/x86-64"));
 
-    #[test]
-    fn test_parse_interp_very_long_path() {
-        let input = "libpcre2-8.so.0 => /nix/store/nalqwq0dpzqnp4nfv25370cb17q3wx4j-pcre2-10.44/lib/libpcre2-8.so.0 (0x00007fdf49524000)";
-        assert_eq!(
-            parse_interp(input),
-            vec![PathBuf::from(
-                "/nix/store/nalqwq0dpzqnp4nfv25370cb17q3wx4j-pcre2-10.44/lib/libpcre2-8.so.0"
-            )]
+        let expanded32 = expand_dst(
+            "/usr/$LIB",
+            Path::new("/opt/app/bin"),
+            Class::ELF32,
+            "i686",
         );
+        assert_eq!(expanded32, PathBuf::from("/usr/lib"));
     }
 
     #[test]
-    fn test_parse_interp_many_paths() {
-        let input = "        linux-vdso.so.1 =>  (0x00007fffd33f2000)
-        libdl.so.2 => /lib64/libdl.so.2 (0x00007f70f7855000)
-        librt.so.1 => /lib64/librt.so.1 (0x00007f70f764d000)
-        libstdc++.so.6 => /lib64/libstdc++.so.6 (0x00007f70f7345000)
-        libm.so.6 => /lib64/libm.so.6 (0x00007f70f7043000)
-        libgcc_s.so.1 => /lib64/libgcc_s.so.1 (0x00007f70f6e2d000)
-        libpthread.so.0 => /lib64/libpthread.so.0 (0x00007f70f6c10000)
-        libc.so.6 => /lib64/libc.so.6 (0x00007f70f684f000)
-        /lib64/ld-linux-x86-64.so.2 (0x00007f70f7a61000)
-";
+    fn test_parse_cache_old_maps_soname_to_path() {
+        // Hand-build a minimal old-format cache with a single entry whose
+        // soname is "libc.so.6" pointing at "/lib64/libc.so.6".
+        let soname = b"libc.so.6\0";
+        let path = b"/lib64/libc.so.6\0";
+        let key = 0u32;
+        let value = soname.len() as u32;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(CACHEMAGIC_OLD);
+        data.extend_from_slice(&1u32.to_le_bytes()); // nlibs
+        data.extend_from_slice(&0i32.to_le_bytes()); // flags
+        data.extend_from_slice(&key.to_le_bytes());
+        data.extend_from_slice(&value.to_le_bytes());
+        data.extend_from_slice(soname);
+        data.extend_from_slice(path);
+
+        let map = parse_cache_old(&data).expect("cache should parse");
         assert_eq!(
-            parse_interp(input),
-            vec![
-                PathBuf::from("/lib64/libdl.so.2"),
-                PathBuf::from("/lib64/librt.so.1"),
-                PathBuf::from("/lib64/libstdc++.so.6"),
-                PathBuf::from("/lib64/libm.so.6"),
-                PathBuf::from("/lib64/libgcc_s.so.1"),
-                PathBuf::from("/lib64/libpthread.so.0"),
-                PathBuf::from("/lib64/libc.so.6"),
-            ]
+            map.get("libc.so.6"),
+            Some(&PathBuf::from("/lib64/libc.so.6"))
         );
     }
 }